@@ -0,0 +1,42 @@
+#pragma once
+
+#include "types.hpp"
+
+// Anything that can be mapped on the bus with Memory::map.
+class BusDevice {
+public:
+    virtual ~BusDevice() {}
+
+    // The address is relative to the start of the mapping, the width is the
+    // access size in bytes (1, 2 or 4).
+    virtual u32 read(u32 address, u32 width) = 0;
+    virtual void write(u32 address, u32 value, u32 width) = 0;
+
+protected:
+    // Most registers are words. These give the part of a register that an
+    // access of width bytes at address sees, and the register after such a
+    // write.
+    static u32 extract(u32 reg, u32 address, u32 width) {
+        return (reg >> ((address & 3) * 8)) & widthMask(width);
+    }
+
+    static u32 merge(u32 reg, u32 address, u32 value, u32 width) {
+        u32 shift = (address & 3) * 8;
+        u32 bits = widthMask(width) << shift;
+        return (reg & ~bits) | ((value << shift) & bits);
+    }
+
+    static u32 widthMask(u32 width) {
+        return width == 4 ? 0xffffffff : (1 << (width * 8)) - 1;
+    }
+};
+
+// Areas where nothing is connected: reads float high, writes are lost.
+class OpenBus : public BusDevice {
+public:
+    u32 read([[maybe_unused]] u32 address, u32 width) override {
+        return widthMask(width);
+    }
+
+    void write([[maybe_unused]] u32 address, [[maybe_unused]] u32 value, [[maybe_unused]] u32 width) override {}
+};
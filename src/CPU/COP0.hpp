@@ -2,6 +2,13 @@
 
 #include "../types.hpp"
 
+// Exception codes, already shifted in position for the Cause register
+#define EXC_ADEL    (4 << 2)    // Address error on load or instruction fetch
+#define EXC_ADES    (5 << 2)    // Address error on store
+#define EXC_IBE     (6 << 2)    // Bus error on instruction fetch
+#define EXC_DBE     (7 << 2)    // Bus error on data load or store
+#define EXC_SYSCALL (8 << 2)
+
 union Cop0SR {
     u32 value;
 
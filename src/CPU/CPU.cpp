@@ -1,13 +1,38 @@
+#include <cstring>
+
 #include "CPU.hpp"
 #include "../Emulator.hpp"
 
 CPU::CPU(Emulator &psx) : psx(psx) {
+    reset(ResetKind::PowerCycle);
+}
+
+void CPU::reset(ResetKind kind) {
     // The CPU starts in KSEG1, at the ROM entry point.
     pc = 0xbfc00000;
 
-    memset(gpr, 0, 32 * sizeof(u32));
+    // The reset signal does not touch the register file, the BIOS
+    // initializes whatever it needs.
+    if (kind == ResetKind::PowerCycle) {
+        memset(gpr, 0, 32 * sizeof(u32));
+        lo = hi = 0;
+    }
 
     isBranching = false;
+
+    loadReg = 0;
+    loadValue = 0;
+    lastWrite = 0;
+
+    cycles = 0;
+    hiLoReady = 0;
+
+    // Reset forces kernel mode, interrupts off and the ROM exception vectors
+    cop0SR.value = 0;
+    cop0SR.fields.bev = 1;
+    cop0Cause = 0;
+    cop0EPC = 0;
+    cop0BadVaddr = 0;
 }
 
 void CPU::run() {
@@ -17,31 +42,35 @@ void CPU::run() {
 }
 
 void CPU::step() {
-    Instruction instruction(psx.memory.read<u32>(pc));
-    InstructionDescriptor descriptor;
-
-    switch (instruction.e.i.op) {
-        case 0:
-            descriptor = specialOperations[instruction.e.r.funct];
-            break;
-        case 1:
-            descriptor = regimmOperations[instruction.e.r.rt];
-            break;
-        case 16:
-            // Actual operation is encoded with RS bits
-            descriptor = cop0Operations[instruction.e.i.rs];
-            break;
-        case 18:
-            emuPanic("CPU", "COP2 Instructions not implemented");
-        default:
-            descriptor = basicOperations[instruction.e.i.op];
-            break;
-    }
+    cycles++;
 
     // Remember if we are in a delay slot (before executing it)
     bool isDelay = isBranching;
 
-    (this->*descriptor.handler)(instruction);
+    // Take the load issued by the previous instruction, this one can still
+    // see the old value and schedule a load of its own
+    u32 delayedReg = loadReg;
+    u32 delayedValue = loadValue;
+    loadReg = 0;
+    lastWrite = 0;
+
+    // A jump to a misaligned address only faults when fetching from there
+    if (checkAddress(pc, 4, EXC_ADEL)) {
+        Instruction instruction(psx.memory.fetch(pc));
+
+        if (psx.memory.takeBusError()) {
+            exception(EXC_IBE);
+        } else {
+            const InstructionDescriptor &descriptor = lookup(instruction);
+            (this->*descriptor.handler)(instruction);
+        }
+    }
+
+    // Commit the delayed load, unless this instruction wrote to the same
+    // register (then its value wins). Both cases write somewhere, possibly
+    // r0, which is forced back to zero right after.
+    gpr[delayedReg == lastWrite ? 0 : delayedReg] = delayedValue;
+    gpr[0] = 0;
 
     // If it was a delay slot, commit the branch target
     if (isDelay) {
@@ -51,6 +80,22 @@ void CPU::step() {
         pc += 4;
 }
 
+const CPU::InstructionDescriptor &CPU::lookup(Instruction instruction) {
+    switch (instruction.e.i.op) {
+        case 0:
+            return specialOperations[instruction.e.r.funct];
+        case 1:
+            return regimmOperations[instruction.e.r.rt];
+        case 16:
+            // Actual operation is encoded with RS bits
+            return cop0Operations[instruction.e.i.rs];
+        case 18:
+            emuPanic("CPU", "COP2 Instructions not implemented");
+        default:
+            return basicOperations[instruction.e.i.op];
+    }
+}
+
 void CPU::branch(bool taken, u32 target) {
     isBranching = true;
 
@@ -62,18 +107,21 @@ void CPU::branch(bool taken, u32 target) {
 }
 
 void CPU::exception(u32 cause) {
+    // If BootExceptionVector bit is set, the exception vectors are from the ROM
+    u32 vector = cop0SR.fields.bev ? 0xbfc00180 : 0x00000080;
+
     if (isBranching) {
+        // In a delay slot, EPC points to the branch, which will be executed
+        // again. The run loop is about to commit the branch target, so that
+        // is where the vector goes.
         cause |= 0x80000000;
         cop0EPC = pc - 4;
-    } else
+        branchPc = vector;
+    } else {
+        // The "- 4" is there because the run loop increments the PC after the instruction
         cop0EPC = pc;
-
-    // If BootExceptionVector bit is set, the exception vectors are from the ROM
-    // The "- 4" is there because the run loop increments the PC after the instruction
-    if (cop0SR.fields.bev)
-        pc = 0xbfc00180 - 4;
-    else
-        pc = 0x00000080 - 4;
+        pc = vector - 4;
+    }
 
     // The low six bits of SR are shifted left by two (killing the top two)
     u32 sr = cop0SR.value & ~0x3f;
@@ -85,6 +133,55 @@ void CPU::exception(u32 cause) {
     cop0Cause = cause;
 }
 
+bool CPU::checkAddress(u32 address, u32 size, u32 cause) {
+    if ((address & (size - 1)) || (cop0SR.fields.ku && (address & 0x80000000))) {
+        cop0BadVaddr = address;
+        exception(cause);
+        return false;
+    }
+
+    return true;
+}
+
+template <typename T>
+bool CPU::load(u32 address, T &value) {
+    if (!checkAddress(address, sizeof(T), EXC_ADEL))
+        return false;
+
+    value = psx.memory.read<T>(address);
+
+    // Bus errors leave BadVaddr alone
+    if (psx.memory.takeBusError()) {
+        exception(EXC_DBE);
+        return false;
+    }
+
+    return true;
+}
+
+template <typename T>
+bool CPU::store(u32 address, T value) {
+    if (!checkAddress(address, sizeof(T), EXC_ADES))
+        return false;
+
+    psx.memory.write(address, value);
+
+    if (psx.memory.takeBusError()) {
+        exception(EXC_DBE);
+        return false;
+    }
+
+    return true;
+}
+
+template bool CPU::load<u8>(u32 address, u8 &value);
+template bool CPU::load<u16>(u32 address, u16 &value);
+template bool CPU::load<u32>(u32 address, u32 &value);
+
+template bool CPU::store<u8>(u32 address, u8 value);
+template bool CPU::store<u16>(u32 address, u16 value);
+template bool CPU::store<u32>(u32 address, u32 value);
+
 CPU::InstructionDescriptor CPU::basicOperations[64] = {
     { "",        nullptr,        0 },   // SPECIAL
     { "",        nullptr,        0 },   // REGIMM
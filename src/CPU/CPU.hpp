@@ -1,6 +1,7 @@
 #pragma once
 
 #include "../types.hpp"
+#include "../Reset.hpp"
 #include "COP0.hpp"
 
 class Emulator;
@@ -66,13 +67,27 @@ class CPU {
 public:
     CPU(Emulator &psx);
 
+    void reset(ResetKind kind);
     void run();
     void step();
 
 private:
+    const InstructionDescriptor &lookup(Instruction instruction);
+
     void branch(bool taken, u32 target);
     void exception(u32 cause);
 
+    // Raises an address error (and returns false) if the access is misaligned
+    // or reaches kernel space from user mode
+    bool checkAddress(u32 address, u32 size, u32 cause);
+
+    // Data accesses, returning false if they raised an exception
+    template <typename T>
+    bool load(u32 address, T &value);
+
+    template <typename T>
+    bool store(u32 address, T value);
+
     Emulator &psx;
 
     // The program counter, contains the address of the current instruction
@@ -81,9 +96,33 @@ private:
     // General purpose registers
     u32 gpr[32];
 
+    // Loaded values only reach the register after the next instruction.
+    // Register 0 doubles as "no load pending": writing to it is harmless as
+    // it gets cleared after every instruction anyway.
+    u32 loadReg;
+    u32 loadValue;
+
+    // The last register written by the current instruction
+    u32 lastWrite;
+
+    // A load also counts as a write, so that it cancels a load to the same
+    // register still in flight from the previous instruction
+    void delayedLoad(u32 reg, u32 value) {
+        loadReg = reg;
+        loadValue = value;
+        lastWrite = reg;
+    }
+
     // Multiplication and addition results
     u32 lo, hi;
 
+    // Instructions executed so far, plus any time spent stalling
+    u64 cycles;
+
+    // MULT/DIV run in the background, HI/LO are ready at this cycle
+    u64 hiLoReady;
+    void waitHiLo();
+
     // Used for branch delay slot
     bool isBranching;
     u32 branchPc;
@@ -92,6 +131,7 @@ private:
     Cop0SR cop0SR;
     u32    cop0Cause;
     u32    cop0EPC;
+    u32    cop0BadVaddr;
 
     // Handler pointers for the 64 possible values of the 6-bit op
     static InstructionDescriptor basicOperations[64];
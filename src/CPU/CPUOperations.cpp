@@ -1,13 +1,39 @@
 #include "CPU.hpp"
 #include "../Emulator.hpp"
 
-#define rD (gpr[i.e.r.rd])
 #define rT (gpr[i.e.i.rt])
 #define rS (gpr[i.e.i.rs])
+
+// Register writes also remember their target, so that a pending load to
+// the same register can be cancelled
+#define wD (gpr[lastWrite = i.e.r.rd])
+#define wT (gpr[lastWrite = i.e.i.rt])
 #define uImm (i.e.i.immediate)
 #define sImm ((s32)(s16) i.e.i.immediate)
 #define Shamt (i.e.r.shamt)
 
+// Division always takes the same time
+#define DIV_CYCLES 36
+
+// Multiplication finishes early when rs has few significant bits
+static u32 multiplyCycles(u32 value, bool isSigned) {
+    if (isSigned && (value & 0x80000000))
+        value = ~value;
+
+    if (value < 0x800)
+        return 6;
+    else if (value < 0x100000)
+        return 9;
+    else
+        return 13;
+}
+
+void CPU::waitHiLo() {
+    // Reading HI/LO too early stalls until the result is there
+    if (cycles < hiLoReady)
+        cycles = hiLoReady;
+}
+
 void CPU::iBadI(CPU::Instruction i) {
     emuPanic("CPU", std::stringstream() << "Invalid instruction " << i.value);
 }
@@ -16,15 +42,15 @@ void CPU::iBadI(CPU::Instruction i) {
 
 void CPU::iADDI(CPU::Instruction i) {
     // TODO: Handle overflows
-    rT = rS + sImm;
+    wT = rS + sImm;
 }
 
 void CPU::iADDIU(CPU::Instruction i) {
-    rT = rS + sImm;
+    wT = rS + sImm;
 }
 
 void CPU::iANDI(CPU::Instruction i) {
-    rT = rS & uImm;
+    wT = rS & uImm;
 }
 
 void CPU::iBEQ(CPU::Instruction i) {
@@ -59,66 +85,81 @@ void CPU::iJ(CPU::Instruction i) {
 void CPU::iJAL(CPU::Instruction i) {
     u32 target = ((pc + 4) & 0xF0000000) | (i.e.j.target << 2);
 
-    gpr[31] = pc + 8;
+    gpr[lastWrite = 31] = pc + 8;
     branch(true, target);
 }
 
 void CPU::iLB(CPU::Instruction i) {
     if (!cop0SR.fields.disableMemoryAccess) {
         u32 address = rS + sImm;
-        rT = (s8) psx.memory.read<u8>(address);
+        u8 value;
+
+        if (load(address, value))
+            delayedLoad(i.e.i.rt, (s8) value);
     } else {
         // This read goes to the instruction cache
         // It should be safe to ignore
-        rT = 0;
+        delayedLoad(i.e.i.rt, 0);
     }
 }
 
 void CPU::iLBU(CPU::Instruction i) {
     if (!cop0SR.fields.disableMemoryAccess) {
         u32 address = rS + sImm;
-        rT = psx.memory.read<u8>(address);
+        u8 value;
+
+        if (load(address, value))
+            delayedLoad(i.e.i.rt, value);
     } else {
         // This read goes to the instruction cache
         // It should be safe to ignore
-        rT = 0;
+        delayedLoad(i.e.i.rt, 0);
     }
 }
 
 void CPU::iLH(CPU::Instruction i) {
     if (!cop0SR.fields.disableMemoryAccess) {
         u32 address = rS + sImm;
-        rT = (s16) psx.memory.read<u16>(address);
+        u16 value;
+
+        if (load(address, value))
+            delayedLoad(i.e.i.rt, (s16) value);
     } else {
         // This read goes to the instruction cache
         // It should be safe to ignore
-        rT = 0;
+        delayedLoad(i.e.i.rt, 0);
     }
 }
 
 void CPU::iLHU(CPU::Instruction i) {
     if (!cop0SR.fields.disableMemoryAccess) {
         u32 address = rS + sImm;
-        rT = psx.memory.read<u16>(address);
+        u16 value;
+
+        if (load(address, value))
+            delayedLoad(i.e.i.rt, value);
     } else {
         // This read goes to the instruction cache
         // It should be safe to ignore
-        rT = 0;
+        delayedLoad(i.e.i.rt, 0);
     }
 }
 
 void CPU::iLUI(CPU::Instruction i) {
-    rT = uImm << 16;
+    wT = uImm << 16;
 }
 
 void CPU::iLW(CPU::Instruction i) {
     if (!cop0SR.fields.disableMemoryAccess) {
         u32 address = rS + sImm;
-        rT = psx.memory.read<u32>(address);
+        u32 value;
+
+        if (load(address, value))
+            delayedLoad(i.e.i.rt, value);
     } else {
         // This read goes to the instruction cache
         // It should be safe to ignore
-        rT = 0;
+        delayedLoad(i.e.i.rt, 0);
     }
 }
 
@@ -135,52 +176,55 @@ void CPU::iLWR([[maybe_unused]] CPU::Instruction i) {
 }
 
 void CPU::iORI(CPU::Instruction i) {
-    rT = rS | uImm;
+    wT = rS | uImm;
 }
 
 void CPU::iSB(CPU::Instruction i) {
+    u32 address = rS + sImm;
+
     if (!cop0SR.fields.disableMemoryAccess) {
-        u32 address = rS + sImm;
-        psx.memory.write(address, (u8)rT);
-    } else {
+        store(address, (u8)rT);
+    } else if (checkAddress(address, 1, EXC_ADES)) {
         // This write goes to the instruction cache
-        // It should be safe to ignore
+        psx.memory.cacheWrite(address, (u8)rT);
     }
 }
 
 void CPU::iSH(CPU::Instruction i) {
+    u32 address = rS + sImm;
+
     if (!cop0SR.fields.disableMemoryAccess) {
-        u32 address = rS + sImm;
-        psx.memory.write(address, (u16)rT);
-    } else {
+        store(address, (u16)rT);
+    } else if (checkAddress(address, 2, EXC_ADES)) {
         // This write goes to the instruction cache
-        // It should be safe to ignore
+        psx.memory.cacheWrite(address, (u16)rT);
     }
 }
 
 void CPU::iSLTI(CPU::Instruction i) {
     if ((s32)rS < sImm) {
-        rT = 1;
+        wT = 1;
     } else {
-        rT = 0;
+        wT = 0;
     }
 }
 
 void CPU::iSLTIU(CPU::Instruction i) {
     if (rS < (u32)sImm) {
-        rT = 1;
+        wT = 1;
     } else {
-        rT = 0;
+        wT = 0;
     }
 }
 
 void CPU::iSW(CPU::Instruction i) {
+    u32 address = rS + sImm;
+
     if (!cop0SR.fields.disableMemoryAccess) {
-        u32 address = rS + sImm;
-        psx.memory.write(address, rT);
-    } else {
+        store(address, rT);
+    } else if (checkAddress(address, 4, EXC_ADES)) {
         // This write goes to the instruction cache
-        // It should be safe to ignore
+        psx.memory.cacheWrite(address, rT);
     }
 }
 
@@ -197,22 +241,22 @@ void CPU::iSWR([[maybe_unused]] CPU::Instruction i) {
 }
 
 void CPU::iXORI(CPU::Instruction i) {
-    rT = rS ^ uImm;
+    wT = rS ^ uImm;
 }
 
 // SPECIAL instructions
 
 void CPU::iADD(Instruction i) {
     // TODO: Handle overflows
-    rT = rS + sImm;
+    wD = rS + rT;
 }
 
 void CPU::iADDU(Instruction i) {
-    rD = rS + rT;
+    wD = rS + rT;
 }
 
 void CPU::iAND(Instruction i) {
-    rD = rS & rT;
+    wD = rS & rT;
 }
 
 void CPU::iBREAK([[maybe_unused]] Instruction i) {
@@ -220,17 +264,39 @@ void CPU::iBREAK([[maybe_unused]] Instruction i) {
 }
 
 void CPU::iDIV(Instruction i) {
-    lo = (s32)rS / (s32)rT;
-    hi = (s32)rS % (s32)rT;
+    s32 n = rS;
+    s32 d = rT;
+
+    if (d == 0) {
+        // No exception, just a recognizable garbage result
+        lo = n >= 0 ? 0xffffffff : 1;
+        hi = n;
+    } else if ((u32)n == 0x80000000 && d == -1) {
+        // The result doesn't fit in 32 bits
+        lo = 0x80000000;
+        hi = 0;
+    } else {
+        lo = n / d;
+        hi = n % d;
+    }
+
+    hiLoReady = cycles + DIV_CYCLES;
 }
 
 void CPU::iDIVU(Instruction i) {
-    lo = rS / rT;
-    hi = rS % rT;
+    if (rT == 0) {
+        lo = 0xffffffff;
+        hi = rS;
+    } else {
+        lo = rS / rT;
+        hi = rS % rT;
+    }
+
+    hiLoReady = cycles + DIV_CYCLES;
 }
 
 void CPU::iJALR(Instruction i) {
-    rD = pc + 8;
+    wD = pc + 8;
     branch(true, rS);
 }
 
@@ -239,95 +305,104 @@ void CPU::iJR(Instruction i) {
 }
 
 void CPU::iMFHI(Instruction i) {
-    rD = hi;
+    waitHiLo();
+    wD = hi;
 }
 
 void CPU::iMFLO(Instruction i) {
-    rD = lo;
+    waitHiLo();
+    wD = lo;
 }
 
-void CPU::iMTHI([[maybe_unused]] Instruction i) {
-    emuPanic("CPU", "Instruction MTHI not implemented");
+void CPU::iMTHI(Instruction i) {
+    hi = rS;
 }
 
-void CPU::iMTLO([[maybe_unused]] Instruction i) {
-    emuPanic("CPU", "Instruction MTLO not implemented");
+void CPU::iMTLO(Instruction i) {
+    lo = rS;
 }
 
-void CPU::iMULT([[maybe_unused]] Instruction i) {
-    emuPanic("CPU", "Instruction MULT not implemented");
+void CPU::iMULT(Instruction i) {
+    s64 result = (s64)(s32)rS * (s64)(s32)rT;
+
+    lo = result & 0xffffffff;
+    hi = (u64)result >> 32;
+
+    hiLoReady = cycles + multiplyCycles(rS, true);
 }
 
 void CPU::iMULTU(Instruction i) {
-    u64 result = rS * rT;
+    u64 result = (u64)rS * rT;
 
     lo = result & 0xffffffff;
     hi = result >> 32;
+
+    hiLoReady = cycles + multiplyCycles(rS, false);
 }
 
 void CPU::iNOR(Instruction i) {
-    rD = ~(rS | rT);
+    wD = ~(rS | rT);
 }
 
 void CPU::iOR(Instruction i) {
-    rD = rS | rT;
+    wD = rS | rT;
 }
 
 void CPU::iSLL(Instruction i) {
-    rD = rT << Shamt;
+    wD = rT << Shamt;
 }
 
 void CPU::iSLLV(Instruction i) {
-    rD = rT << (rS & 0x1f);
+    wD = rT << (rS & 0x1f);
 }
 
 void CPU::iSLT(Instruction i) {
     if ((s32)rS < (s32)rT) {
-        rD = 1;
+        wD = 1;
     } else {
-        rD = 0;
+        wD = 0;
     }
 }
 
 void CPU::iSLTU(Instruction i) {
     if (rS < rT) {
-        rD = 1;
+        wD = 1;
     } else {
-        rD = 0;
+        wD = 0;
     }
 }
 
 void CPU::iSRA(Instruction i) {
-    rD = (s32)rT >> Shamt;
+    wD = (s32)rT >> Shamt;
 }
 
 void CPU::iSRAV(Instruction i) {
-    rD = (s32)rT >> (rS & 0x1f);
+    wD = (s32)rT >> (rS & 0x1f);
 }
 
 void CPU::iSRL(Instruction i) {
-    rD = rT >> Shamt;
+    wD = rT >> Shamt;
 }
 
 void CPU::iSRLV(Instruction i) {
-    rD = rT >> (rS & 0x1f);
+    wD = rT >> (rS & 0x1f);
 }
 
 void CPU::iSUB(Instruction i) {
     // TODO: Handle overflows
-    rD = rS - rT;
+    wD = rS - rT;
 }
 
 void CPU::iSUBU(Instruction i) {
-    rD = rS - rT;
+    wD = rS - rT;
 }
 
 void CPU::iSYSCALL([[maybe_unused]] Instruction i) {
-    exception(0x20);
+    exception(EXC_SYSCALL);
 }
 
 void CPU::iXOR(Instruction i) {
-    rD = rS ^ rT;
+    wD = rS ^ rT;
 }
 
 // REGIMM Instructions
@@ -363,8 +438,17 @@ void CPU::iCTC0([[maybe_unused]] Instruction i) {
 
 void CPU::iMFC0(Instruction i) {
     switch (i.e.r.rd) {
+        case 8:
+            delayedLoad(i.e.i.rt, cop0BadVaddr);
+            break;
         case 12:
-            rT = cop0SR.value;
+            delayedLoad(i.e.i.rt, cop0SR.value);
+            break;
+        case 13:
+            delayedLoad(i.e.i.rt, cop0Cause);
+            break;
+        case 14:
+            delayedLoad(i.e.i.rt, cop0EPC);
             break;
         default:
             emuPanic("CPU", std::stringstream() << "Instruction MFC0 not implemented for register " << i.e.r.rd);
@@ -394,5 +478,9 @@ void CPU::iMTC0(Instruction i) {
 }
 
 void CPU::iRFE([[maybe_unused]] Instruction i) {
-    emuPanic("CPU", "Instruction RFE not implemented");
+    // Pop the interrupt enable / kernel mode stack, the reverse of exception()
+    u32 sr = cop0SR.value & ~0xf;
+    u32 kuIeBits = (cop0SR.value >> 2) & 0xf;
+
+    cop0SR.value = sr | kuIeBits;
 }
@@ -1,86 +1,56 @@
-#include <sstream>
-
 #include "Emulator.hpp"
 
 using namespace std;
 
-Emulator::Emulator() : cpu(*this), memory(*this) {
-    ioShadow = new u8[0x10000];
+Emulator::Emulator()
+    : cpu(*this),
+      memoryControl("IO", 0x1f801000, 0x24, false),    // TODO: Expansion base addresses and sizes, delays
+      ramConfig("IO", 0x1f801060, 4, false),           // TODO: RAM_CONFIG
+      timers("RCNT", 0x1f801100, 0x30, false),         // TODO: RCNT
+      spu("SPU", 0x1f801c00, 0x400, false),            // TODO: SPU
+      unknownIO("IO", 0x1f800400, 0xfc00, true) {
+    // The catch-all goes first, the devices below take their slots over
+    memory.map(0x1f800400, 0xfc00, &unknownIO);
+
+    memory.map(0x1f801000, 0x24, &memoryControl);
+    memory.map(0x1f801060, 4, &ramConfig);
+    memory.map(0x1f801070, 8, &interrupts);
+    memory.map(0x1f801100, 0x30, &timers);
+    memory.map(0x1f801820, 8, &mdec);
+    memory.map(0x1f801c00, 0x400, &spu);
+    memory.map(0x1f802000, 0x2000, &expansion2);
 }
 
-Emulator::~Emulator() {
-    delete[] ioShadow;
+void Emulator::reset(ResetKind kind) {
+    cpu.reset(kind);
+    memory.reset(kind);
+    mdec.reset(kind);
+    expansion2.reset(kind);
+    interrupts.reset(kind);
+
+    memoryControl.reset(kind);
+    ramConfig.reset(kind);
+    timers.reset(kind);
+    spu.reset(kind);
+    unknownIO.reset(kind);
 }
 
 void Emulator::loadBios(std::string path) {
     memory.loadBios(path);
 }
 
-void Emulator::run() {
-    cpu.run();
+void Emulator::loadExpansion(std::string path) {
+    memory.loadExpansion(path);
 }
 
-template <typename T>
-T Emulator::ioRead(u32 address) {
-    T *fallback = (T *) &ioShadow[address];
-
-    switch (address) {
-        case 0x1c00 ... 0x1fff:    // TODO: SPU
-        case 0x1074:
-            return *fallback;
-        default: {
-            log('W', "IO", stringstream() << "Read from unknown I/O location " << hex << address);
-
-            return *fallback;
-        }
-    }
+void Emulator::setRamSize(u32 size) {
+    memory.setRamSize(size);
 }
 
-template <typename T>
-void Emulator::ioWrite(u32 address, T value) {
-    u32 *fallback = (u32 *) &ioShadow[address];
-
-    switch (address) {
-        case 0x1000:    // TODO: Expansion 1 base address
-        case 0x1004:    // TODO: Expansion 2 base address
-        case 0x1008:    // TODO: Expansion 1 Size
-        case 0x100c:    // TODO: Expansion 3 Size (??)
-        case 0x1010:    // TODO: ROM_CONFIG
-        case 0x1014:    // TODO: SPU_DELAY
-        case 0x1018:    // TODO: CDROM_DELAY
-        case 0x101c:    // TODO: Expansion 2 Size
-        case 0x1020:    // TODO: COM_DELAY
-        case 0x1060:    // TODO: RAM_CONFIG
-        case 0x1c00 ... 0x1fff:    // TODO: SPU
-            *fallback = value;
-            break;
-        case 0x1070:
-            // I_STAT. Acknoledges interrupts by writing bits 0.
-            // 1s are left as they are.
-            *fallback &= value;
-            break;
-        case 0x1074:
-            *fallback = value;
-            break;
-        case 0x1100 ... 0x112c:
-            // TODO: RCNT
-            break;
-        case 0x2041:
-            // BIOS POST Status
-            *fallback = value;
-            break;
-        default: {
-            log('W', "IO", stringstream() << "Write to unknown I/O location " << hex << address << " - value: " << value);
-
-            *fallback = value;
-        }
-    }
+void Emulator::setPowerOnRam(PowerOnState state, u32 seed) {
+    memory.setPowerOnRam(state, seed);
 }
 
-template u8 Emulator::ioRead<u8>(uint32_t address);
-template u16 Emulator::ioRead<u16>(uint32_t address);
-template u32 Emulator::ioRead<u32>(uint32_t address);
-
-template void Emulator::ioWrite<u8>(uint32_t address, u8 value);
-template void Emulator::ioWrite<u16>(uint32_t address, u16 value);
-template void Emulator::ioWrite<u32>(uint32_t address, u32 value);
+void Emulator::run() {
+    cpu.run();
+}
@@ -1,29 +1,36 @@
 #pragma once
 
 #include "CPU/CPU.hpp"
+#include "Expansion/Expansion2.hpp"
+#include "Interrupts/InterruptController.hpp"
+#include "IOShadow.hpp"
+#include "MDEC/MDEC.hpp"
 #include "Memory.hpp"
 
 class Emulator {
 public:
     Emulator();
-    ~Emulator();
 
+    void reset(ResetKind kind);
     void loadBios(std::string path);
+    void loadExpansion(std::string path);
+    void setRamSize(u32 size);
+    void setPowerOnRam(PowerOnState state, u32 seed = 0);
     void run();
 
-    template <typename T>
-    T ioRead(u32 address);
-
-    template <typename T>
-    void ioWrite(u32 address, T value);
-
     CPU cpu;
     Memory memory;
+    MDEC mdec;
+    Expansion2 expansion2;
+    InterruptController interrupts;
 
 private:
-    // For many IO ports we'll have to ignore writes (for lack of understanding)
-    // In those case, we'll make sure that a read from the same port will have
-    // the last written value, effectively an extra 65kb of memory.
-    // Ideally this shouldn't exist and every port is fully mapped.
-    u8 *ioShadow;
-};
\ No newline at end of file
+    // Registers that are known but not emulated yet
+    IOShadow memoryControl;
+    IOShadow ramConfig;
+    IOShadow timers;
+    IOShadow spu;
+
+    // Everything else in the I/O area
+    IOShadow unknownIO;
+};
@@ -0,0 +1,70 @@
+#include "Expansion2.hpp"
+
+Expansion2::Expansion2() {
+    reset(ResetKind::PowerCycle);
+}
+
+void Expansion2::reset([[maybe_unused]] ResetKind kind) {
+    post = 0;
+    line.clear();
+}
+
+// Expansion 2 is an 8-bit bus, wider accesses are split in bytes
+u32 Expansion2::read(u32 address, u32 width) {
+    u32 value = 0;
+    for (u32 i = 0; i < width; i++)
+        value |= readRegister(address + i) << (i * 8);
+
+    return value;
+}
+
+void Expansion2::write(u32 address, u32 value, u32 width) {
+    for (u32 i = 0; i < width; i++)
+        writeRegister(address + i, (value >> (i * 8)) & 0xff);
+}
+
+u8 Expansion2::readRegister(u32 address) {
+    switch (address) {
+        case 0x21:
+            // DUART status register A. The transmitter is always ready and
+            // empty, there is never anything to receive.
+            return 0x0c;
+        case 0x41:
+            return post;
+        default:
+            // Open bus, like when nothing is connected
+            return 0xff;
+    }
+}
+
+void Expansion2::writeRegister(u32 address, u8 value) {
+    switch (address) {
+        case 0x20 ... 0x22:
+        case 0x24 ... 0x2f:
+            // DUART mode, clock select, command, aux control, interrupt,
+            // channel B and output port registers. Baud rates and such
+            // don't matter to us.
+            break;
+        case 0x23:
+            // DUART transmit holding register A
+            transmit(value);
+            break;
+        case 0x41:
+            post = value;
+            log('I', "POST", std::stringstream() << "Code " << std::hex << (u32) post);
+            break;
+        default:
+            log('W', "EXP2", std::stringstream() << "Write to unknown location " << std::hex << address << " - value: " << (u32) value);
+    }
+}
+
+void Expansion2::transmit(char c) {
+    if (c == '\r')
+        return;
+
+    if (c == '\n') {
+        log('I', "TTY", line);
+        line.clear();
+    } else
+        line += c;
+}
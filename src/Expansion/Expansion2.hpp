@@ -0,0 +1,29 @@
+#pragma once
+
+#include "../types.hpp"
+#include "../BusDevice.hpp"
+#include "../Reset.hpp"
+
+// Expansion 2 (0x1f802000), where development hardware lives: a DUART for a
+// serial console and the POST display.
+class Expansion2 : public BusDevice {
+public:
+    Expansion2();
+
+    void reset(ResetKind kind);
+
+    u32 read(u32 address, u32 width) override;
+    void write(u32 address, u32 value, u32 width) override;
+
+private:
+    u8 readRegister(u32 address);
+    void writeRegister(u32 address, u8 value);
+
+    void transmit(char c);
+
+    // Last value written to the POST register
+    u8 post;
+
+    // The serial line being received, logged on newlines
+    std::string line;
+};
@@ -0,0 +1,33 @@
+#include <algorithm>
+
+#include "IOShadow.hpp"
+#include "log.hpp"
+
+// Mappings in the I/O page cover whole 16-byte slots
+IOShadow::IOShadow(std::string component, u32 base, u32 size, bool logAccesses)
+    : component(component), base(base), logAccesses(logAccesses), data((size + 15) & ~15) {
+}
+
+void IOShadow::reset([[maybe_unused]] ResetKind kind) {
+    // I/O registers go back to their defaults on any kind of reset
+    std::fill(data.begin(), data.end(), 0);
+}
+
+u32 IOShadow::read(u32 address, u32 width) {
+    if (logAccesses)
+        log('W', component, std::stringstream() << "Read from unknown I/O location " << std::hex << base + address);
+
+    u32 value = 0;
+    for (u32 i = 0; i < width; i++)
+        value |= data[address + i] << (i * 8);
+
+    return value;
+}
+
+void IOShadow::write(u32 address, u32 value, u32 width) {
+    if (logAccesses)
+        log('W', component, std::stringstream() << "Write to unknown I/O location " << std::hex << base + address << " - value: " << value);
+
+    for (u32 i = 0; i < width; i++)
+        data[address + i] = value >> (i * 8);
+}
@@ -0,0 +1,30 @@
+#pragma once
+
+#include <string>
+#include <vector>
+
+#include "types.hpp"
+#include "BusDevice.hpp"
+#include "Reset.hpp"
+
+// Stand-in for I/O registers we don't emulate (for lack of understanding, or
+// time). Writes are remembered so that a read from the same port returns the
+// last written value. Ideally this shouldn't exist and every port is mapped.
+class IOShadow : public BusDevice {
+public:
+    // Registers we know about but ignore are quiet, the rest get logged. The
+    // base is only used to print full addresses.
+    IOShadow(std::string component, u32 base, u32 size, bool logAccesses);
+
+    void reset(ResetKind kind);
+
+    u32 read(u32 address, u32 width) override;
+    void write(u32 address, u32 value, u32 width) override;
+
+private:
+    std::string component;
+    u32 base;
+    bool logAccesses;
+
+    std::vector<u8> data;
+};
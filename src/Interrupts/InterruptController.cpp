@@ -0,0 +1,38 @@
+#include "InterruptController.hpp"
+#include "../log.hpp"
+
+InterruptController::InterruptController() {
+    reset(ResetKind::PowerCycle);
+}
+
+void InterruptController::reset([[maybe_unused]] ResetKind kind) {
+    status = 0;
+    mask = 0;
+}
+
+u32 InterruptController::read(u32 address, u32 width) {
+    switch (address & ~3) {
+        case 0:
+            return extract(status, address, width);
+        case 4:
+            return extract(mask, address, width);
+        default:
+            log('W', "IRQ", std::stringstream() << "Read from unknown register " << std::hex << address);
+            return 0;
+    }
+}
+
+void InterruptController::write(u32 address, u32 value, u32 width) {
+    switch (address & ~3) {
+        case 0:
+            // Interrupts are acknowledged by writing 0s, 1s leave them as
+            // they are. So do the bytes the write doesn't cover.
+            status &= merge(0xffffffff, address, value, width);
+            break;
+        case 4:
+            mask = merge(mask, address, value, width);
+            break;
+        default:
+            log('W', "IRQ", std::stringstream() << "Write to unknown register " << std::hex << address);
+    }
+}
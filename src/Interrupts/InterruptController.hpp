@@ -0,0 +1,21 @@
+#pragma once
+
+#include "../types.hpp"
+#include "../BusDevice.hpp"
+#include "../Reset.hpp"
+
+// I_STAT (0x1f801070) and I_MASK (0x1f801074). Nothing raises interrupts yet,
+// but the BIOS acknowledges them and expects the registers to behave.
+class InterruptController : public BusDevice {
+public:
+    InterruptController();
+
+    void reset(ResetKind kind);
+
+    u32 read(u32 address, u32 width) override;
+    void write(u32 address, u32 value, u32 width) override;
+
+private:
+    u32 status;
+    u32 mask;
+};
@@ -0,0 +1,346 @@
+#include <algorithm>
+#include <cstring>
+
+#include "MDEC.hpp"
+
+// Position in the 8x8 matrix of the n-th coefficient of the RLE stream
+static const u8 zagzig[64] = {
+     0,  1,  8, 16,  9,  2,  3, 10,
+    17, 24, 32, 25, 18, 11,  4,  5,
+    12, 19, 26, 33, 40, 48, 41, 34,
+    27, 20, 13,  6,  7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36,
+    29, 22, 15, 23, 30, 37, 44, 51,
+    58, 59, 52, 45, 38, 31, 39, 46,
+    53, 60, 61, 54, 47, 55, 62, 63
+};
+
+static s32 signed10(u32 value) {
+    return ((s32) (value << 22)) >> 22;
+}
+
+MDEC::MDEC() {
+    reset(ResetKind::PowerCycle);
+}
+
+void MDEC::reset(ResetKind kind) {
+    // The tables are RAM inside the chip, the BIOS uploads them anyway
+    if (kind == ResetKind::PowerCycle) {
+        memset(luminanceQuant, 0, sizeof(luminanceQuant));
+        memset(colorQuant, 0, sizeof(colorQuant));
+        memset(scaleTable, 0, sizeof(scaleTable));
+    }
+
+    command = 0;
+    remainingWords = 0;
+    parameterIndex = 0;
+
+    depth = MDECDepth::Bit4;
+    outputSigned = false;
+    outputBit15 = false;
+
+    inBlock = false;
+    currentBlock = 0;
+
+    output.clear();
+    outputWord = 0;
+    outputBytes = 0;
+
+    dataInRequest = false;
+    dataOutRequest = false;
+}
+
+u32 MDEC::read(u32 address, u32 width) {
+    switch (address & ~3) {
+        case 0: {
+            // The FIFO only moves on once the last byte of a word is read
+            bool pop = (address & 3) + width == 4;
+            return extract(readData(pop), address, width);
+        }
+        case 4:
+            return extract(readStatus(), address, width);
+        default:
+            log('W', "MDEC", std::stringstream() << "Read from unknown register " << std::hex << address);
+            return 0;
+    }
+}
+
+void MDEC::write(u32 address, u32 value, u32 width) {
+    // Commands and parameters are whole words, there is nothing sensible to
+    // do with a part of one
+    if (width != 4) {
+        log('W', "MDEC", std::stringstream() << "Ignoring " << width << "-byte write to " << std::hex << address);
+        return;
+    }
+
+    switch (address) {
+        case 0:
+            writeCommand(value);
+            break;
+        case 4:
+            writeControl(value);
+            break;
+        default:
+            log('W', "MDEC", std::stringstream() << "Write to unknown register " << std::hex << address);
+    }
+}
+
+u32 MDEC::readData(bool pop) {
+    if (output.empty()) {
+        log('W', "MDEC", "Read from empty output FIFO");
+        return 0;
+    }
+
+    u32 value = output.front();
+    if (pop)
+        output.pop_front();
+    return value;
+}
+
+void MDEC::writeCommand(u32 value) {
+    if (remainingWords > 0)
+        writeParameter(value);
+    else
+        startCommand(value);
+}
+
+u32 MDEC::readStatus() {
+    u32 status = 0;
+
+    status |= (output.empty() ? 1 : 0) << 31;
+    status |= (remainingWords > 0 ? 1 : 0) << 29;
+    status |= (dataInRequest && remainingWords > 0 ? 1 : 0) << 28;
+    status |= (dataOutRequest && !output.empty() ? 1 : 0) << 27;
+    status |= (u32) depth << 25;
+    status |= (outputSigned ? 1 : 0) << 24;
+    status |= (outputBit15 ? 1 : 0) << 23;
+
+    // Blocks arrive as Cr, Cb, Y1..Y4, but are reported as Y1..Y4 = 0..3,
+    // Cr = 4, Cb = 5. Monochrome data is always reported as 4.
+    u32 block = 4;
+    if (depth == MDECDepth::Bit24 || depth == MDECDepth::Bit15)
+        block = currentBlock < 2 ? currentBlock + 4 : currentBlock - 2;
+    status |= block << 16;
+
+    // Words left minus one, 0xffff when idle
+    status |= (remainingWords - 1) & 0xffff;
+
+    return status;
+}
+
+void MDEC::writeControl(u32 value) {
+    if (value & 0x80000000)
+        reset(ResetKind::Soft);
+
+    dataInRequest = value & (1 << 30);
+    dataOutRequest = value & (1 << 29);
+}
+
+void MDEC::startCommand(u32 value) {
+    command = value >> 29;
+    parameterIndex = 0;
+
+    switch (command) {
+        case 1:
+            // Decode Macroblock(s)
+            depth = (MDECDepth) ((value >> 27) & 3);
+            outputSigned = value & (1 << 26);
+            outputBit15 = value & (1 << 25);
+            remainingWords = value & 0xffff;
+
+            inBlock = false;
+            currentBlock = 0;
+            break;
+        case 2:
+            // Set Quant Table(s): luminance only, or luminance and color
+            remainingWords = (value & 1) ? 32 : 16;
+            break;
+        case 3:
+            // Set Scale Table
+            remainingWords = 32;
+            break;
+        default:
+            log('W', "MDEC", std::stringstream() << "Unknown command " << std::hex << value);
+            command = 0;
+            remainingWords = 0;
+    }
+}
+
+void MDEC::writeParameter(u32 value) {
+    switch (command) {
+        case 1:
+            decodeHalfword(value & 0xffff);
+            decodeHalfword(value >> 16);
+            break;
+        case 2:
+            for (u32 i = 0; i < 4; i++) {
+                u32 index = parameterIndex * 4 + i;
+                u8 byte = value >> (i * 8);
+
+                if (index < 64)
+                    luminanceQuant[index] = byte;
+                else
+                    colorQuant[index - 64] = byte;
+            }
+            break;
+        case 3:
+            scaleTable[parameterIndex * 2] = value & 0xffff;
+            scaleTable[parameterIndex * 2 + 1] = value >> 16;
+            break;
+    }
+
+    parameterIndex++;
+    remainingWords--;
+}
+
+void MDEC::decodeHalfword(u16 value) {
+    bool color = depth == MDECDepth::Bit24 || depth == MDECDepth::Bit15;
+    const u8 *quant = (color && currentBlock < 2) ? colorQuant : luminanceQuant;
+    s16 *block = blocks[currentBlock];
+    s32 coefficientValue;
+
+    if (!inBlock) {
+        // Padding between blocks
+        if (value == 0xfe00)
+            return;
+
+        memset(block, 0, 64 * sizeof(s16));
+        inBlock = true;
+        coefficient = 0;
+        qScale = value >> 10;
+
+        // The DC coefficient is not scaled by qScale
+        coefficientValue = signed10(value & 0x3ff) * quant[0];
+    } else {
+        coefficient += (value >> 10) + 1;
+
+        if (coefficient > 63) {
+            finishBlock();
+            return;
+        }
+
+        coefficientValue = (signed10(value & 0x3ff) * quant[coefficient] * qScale + 4) / 8;
+    }
+
+    // A qScale of zero means the block is stored without quantization and in
+    // plain order rather than zigzag
+    if (qScale == 0) {
+        coefficientValue = signed10(value & 0x3ff) * 2;
+        block[coefficient] = std::clamp(coefficientValue, -0x400, 0x3ff);
+    } else
+        block[zagzig[coefficient]] = std::clamp(coefficientValue, -0x400, 0x3ff);
+}
+
+void MDEC::finishBlock() {
+    inBlock = false;
+    idct(blocks[currentBlock]);
+
+    if (depth == MDECDepth::Bit4 || depth == MDECDepth::Bit8) {
+        outputMono();
+        return;
+    }
+
+    if (++currentBlock == 6) {
+        outputColor();
+        currentBlock = 0;
+    }
+}
+
+void MDEC::idct(s16 *block) {
+    s32 temp[64];
+
+    for (u32 x = 0; x < 8; x++) {
+        for (u32 y = 0; y < 8; y++) {
+            s32 sum = 0;
+            for (u32 z = 0; z < 8; z++)
+                sum += block[z * 8 + x] * scaleTable[z * 8 + y];
+            temp[x + y * 8] = sum;
+        }
+    }
+
+    for (u32 x = 0; x < 8; x++) {
+        for (u32 y = 0; y < 8; y++) {
+            s64 sum = 0;
+            for (u32 z = 0; z < 8; z++)
+                sum += (s64) temp[z + y * 8] * scaleTable[z * 8 + x];
+
+            // Round, then keep the 9-bit signed result the hardware produces
+            s32 result = (s32) ((sum >> 32) + ((sum >> 31) & 1));
+            result = ((s32) ((u32) result << 23)) >> 23;
+            block[x + y * 8] = std::clamp(result, -128, 127);
+        }
+    }
+}
+
+void MDEC::outputColor() {
+    const s16 *cr = blocks[0];
+    const s16 *cb = blocks[1];
+
+    for (u32 y = 0; y < 16; y++) {
+        for (u32 x = 0; x < 16; x++) {
+            // Y1 is top left, Y2 top right, Y3 bottom left, Y4 bottom right
+            const s16 *luma = blocks[2 + (y / 8) * 2 + (x / 8)];
+            s32 l = luma[(y % 8) * 8 + (x % 8)];
+
+            // Chroma is subsampled, one value for each 2x2 pixels
+            s32 r = cr[(y / 2) * 8 + (x / 2)];
+            s32 b = cb[(y / 2) * 8 + (x / 2)];
+
+            // 1.402 Cr, -0.3437 Cb - 0.7143 Cr, 1.772 Cb in 8.8 fixed point
+            s32 g = (-88 * b - 183 * r) >> 8;
+            r = (359 * r) >> 8;
+            b = (454 * b) >> 8;
+
+            u8 red = std::clamp(l + r, -128, 127);
+            u8 green = std::clamp(l + g, -128, 127);
+            u8 blue = std::clamp(l + b, -128, 127);
+
+            if (!outputSigned) {
+                red ^= 0x80;
+                green ^= 0x80;
+                blue ^= 0x80;
+            }
+
+            if (depth == MDECDepth::Bit24) {
+                pushByte(red);
+                pushByte(green);
+                pushByte(blue);
+            } else {
+                u16 pixel = (outputBit15 ? 0x8000 : 0) |
+                            ((blue >> 3) << 10) | ((green >> 3) << 5) | (red >> 3);
+                pushByte(pixel);
+                pushByte(pixel >> 8);
+            }
+        }
+    }
+}
+
+void MDEC::outputMono() {
+    const s16 *luma = blocks[0];
+
+    for (u32 i = 0; i < 64; i += 2) {
+        u8 first = std::clamp<s32>(luma[i], -128, 127);
+        u8 second = std::clamp<s32>(luma[i + 1], -128, 127);
+
+        if (!outputSigned) {
+            first ^= 0x80;
+            second ^= 0x80;
+        }
+
+        if (depth == MDECDepth::Bit8) {
+            pushByte(first);
+            pushByte(second);
+        } else
+            pushByte((first >> 4) | (second & 0xf0));
+    }
+}
+
+void MDEC::pushByte(u8 value) {
+    outputWord |= value << (outputBytes * 8);
+
+    if (++outputBytes == 4) {
+        output.push_back(outputWord);
+        outputWord = 0;
+        outputBytes = 0;
+    }
+}
@@ -0,0 +1,82 @@
+#pragma once
+
+#include <deque>
+
+#include "../types.hpp"
+#include "../BusDevice.hpp"
+#include "../Reset.hpp"
+
+// Output depth of a Decode Macroblock command
+enum class MDECDepth : u32 {
+    Bit4  = 0,
+    Bit8  = 1,
+    Bit24 = 2,
+    Bit15 = 3
+};
+
+// The Macroblock Decoder, which turns compressed (RLE + DCT) image data into
+// pixels, mostly used for FMVs.
+class MDEC : public BusDevice {
+public:
+    MDEC();
+
+    void reset(ResetKind kind);
+
+    u32 read(u32 address, u32 width) override;
+    void write(u32 address, u32 value, u32 width) override;
+
+private:
+    // 0x1f801820: commands and parameters in, decoded data out. Partial
+    // reads peek at the word without removing it.
+    u32 readData(bool pop);
+    void writeCommand(u32 value);
+
+    // 0x1f801824: status out, control in
+    u32 readStatus();
+    void writeControl(u32 value);
+
+    void startCommand(u32 command);
+    void writeParameter(u32 value);
+
+    void decodeHalfword(u16 value);
+    void finishBlock();
+    void idct(s16 *block);
+    void outputColor();
+    void outputMono();
+    void pushByte(u8 value);
+
+    // The command currently receiving parameters (0 if none)
+    u32 command;
+    u32 remainingWords;
+    u32 parameterIndex;
+
+    // Decode Macroblock settings
+    MDECDepth depth;
+    bool outputSigned;
+    bool outputBit15;
+
+    // Quantization tables, in zigzag order
+    u8 luminanceQuant[64];
+    u8 colorQuant[64];
+
+    // The IDCT matrix
+    s16 scaleTable[64];
+
+    // RLE decoding state for the block being received
+    bool inBlock;
+    u32 coefficient;
+    u32 qScale;
+    u32 currentBlock;
+
+    // Cr, Cb, Y1, Y2, Y3, Y4 (mono uses just one Y block)
+    s16 blocks[6][64];
+
+    // Decoded data waiting to be read, and the word being filled. Every
+    // output format produces a multiple of 4 bytes per block.
+    std::deque<u32> output;
+    u32 outputWord;
+    u32 outputBytes;
+
+    bool dataInRequest;
+    bool dataOutRequest;
+};
@@ -1,15 +1,184 @@
-#include "Emulator.hpp"
+#include <algorithm>
+#include <cstring>
+#include <random>
+
 #include "Memory.hpp"
 #include "log.hpp"
 
-Memory::Memory(Emulator &psx) : psx(psx) {
-    mainRam = new u8[RAM_SIZE];
+Memory::Memory() : scratchpad(*this), cacheControlPort(*this) {
+    // Allocate for the largest configuration, so the size can change later
+    mainRam = new u8[RAM_SIZE_DEVKIT];
+    ramSize = RAM_SIZE;
     biosRom = new u8[BIOS_SIZE];
+
+    expansionRom = nullptr;
+    expansionSize = 0;
+
+    readPages = new u8*[pageCount];
+    writePages = new u8*[pageCount];
+    mapPages();
+
+    devicePages = new DeviceMapping[pageCount]();
+    ioDevices = new DeviceMapping[ioSlotCount]();
+
+    map(SCRATCHPAD_BASE, SCRATCHPAD_SIZE, &scratchpad);
+
+    // Writes to the BIOS are lost, reads never get to the device
+    map(BIOS_BASE, BIOS_SIZE, &openBus);
+    map(CACHE_CONTROL, 4, &cacheControlPort);
+
+    // Nothing drives the bus in the expansion areas, unless a cartridge
+    // is loaded. Its pages are plain memory, which takes precedence.
+    map(EXPANSION1_BASE, EXPANSION1_SIZE, &openBus);
+    map(EXPANSION3_BASE, EXPANSION3_SIZE, &openBus);
+
+    powerOnState = PowerOnState::Zero;
+    powerOnSeed = 0;
+
+    reset(ResetKind::PowerCycle);
 }
 
 Memory::~Memory() {
     delete[] mainRam;
     delete[] biosRom;
+    delete[] expansionRom;
+    delete[] readPages;
+    delete[] writePages;
+    delete[] devicePages;
+    delete[] ioDevices;
+}
+
+void Memory::mapPages() {
+    std::fill(readPages, readPages + pageCount, nullptr);
+    std::fill(writePages, writePages + pageCount, nullptr);
+
+    // Main memory is mirrored over the first 8MB (four times on retail units)
+    for (u32 page = 0; page < (0x00800000 >> pageShift); page++) {
+        u8 *pointer = &mainRam[(page << pageShift) & (ramSize - 1)];
+
+        readPages[page] = pointer;
+        writePages[page] = pointer;
+    }
+
+    // The BIOS can only be read, writes go to an open bus device
+    for (u32 page = 0; page < (BIOS_SIZE >> pageShift); page++)
+        readPages[(BIOS_BASE >> pageShift) + page] = &biosRom[page << pageShift];
+
+    // Same for a cartridge. The rest of expansion 1 stays open bus.
+    for (u32 page = 0; page < (expansionSize >> pageShift); page++)
+        readPages[(EXPANSION1_BASE >> pageShift) + page] = &expansionRom[page << pageShift];
+}
+
+void Memory::map(u32 base, u32 size, BusDevice *device) {
+    u32 lower = normalizeAddress(base);
+
+    if ((lower >> pageShift) == ioPage) {
+        u32 first = (lower & (pageSize - 1)) >> ioSlotShift;
+        u32 count = (size + (1 << ioSlotShift) - 1) >> ioSlotShift;
+
+        for (u32 slot = first; slot < first + count; slot++)
+            ioDevices[slot] = { device, lower };
+    } else {
+        u32 first = lower >> pageShift;
+        u32 count = (size + pageSize - 1) >> pageShift;
+
+        for (u32 page = first; page < first + count; page++)
+            devicePages[page] = { device, lower };
+    }
+}
+
+const Memory::DeviceMapping &Memory::deviceAt(u32 lower) {
+    u32 page = lower >> pageShift;
+
+    if (page == ioPage)
+        return ioDevices[(lower & (pageSize - 1)) >> ioSlotShift];
+
+    return devicePages[page];
+}
+
+void Memory::reset(ResetKind kind) {
+    // A power cycle recreates the same power-on garbage (in main RAM and the
+    // scratchpad), a soft reset leaves them alone. The BIOS is a ROM and
+    // survives both.
+    if (kind == ResetKind::PowerCycle)
+        fillRam();
+
+    cacheControl = 0;
+    busError = false;
+
+    for (CacheLine &line : icache)
+        line.valid[0] = line.valid[1] = line.valid[2] = line.valid[3] = false;
+}
+
+bool Memory::takeBusError() {
+    bool error = busError;
+    busError = false;
+    return error;
+}
+
+bool Memory::scratchpadEnabled() {
+    // Both enable bits need to be set
+    return (cacheControl & 0x88) == 0x88;
+}
+
+bool Memory::codeCacheEnabled() {
+    return cacheControl & (1 << 11);
+}
+
+bool Memory::tagTestEnabled() {
+    return cacheControl & (1 << 2);
+}
+
+u32 Memory::fetch(u32 address) {
+    // Only KUSEG and KSEG0 are cached
+    if (!codeCacheEnabled() || address >= 0xa0000000)
+        return read<u32>(address);
+
+    u32 lower = normalizeAddress(address);
+    CacheLine &line = icache[(lower >> 4) & (ICACHE_LINES - 1)];
+    u32 tag = lower >> 12;
+    u32 word = (lower >> 2) & 3;
+
+    if (line.tag != tag || !line.valid[word]) {
+        // A miss refills the line from the missing word to its end
+        line.tag = tag;
+        line.valid[0] = line.valid[1] = line.valid[2] = line.valid[3] = false;
+
+        for (u32 i = word; i < 4; i++) {
+            line.data[i] = read<u32>((address & ~0xf) + i * 4);
+
+            // The refill stops at a bus error, the word must not look cached
+            if (busError)
+                break;
+
+            line.valid[i] = true;
+        }
+    }
+
+    return line.data[word];
+}
+
+template <typename T>
+void Memory::cacheWrite(u32 address, T value) {
+    // With the cache disabled, isolated stores go nowhere
+    if (!codeCacheEnabled())
+        return;
+
+    u32 lower = normalizeAddress(address);
+    CacheLine &line = icache[(lower >> 4) & (ICACHE_LINES - 1)];
+    u32 word = (lower >> 2) & 3;
+
+    if (tagTestEnabled()) {
+        // The store sets the line's tag, and the valid bits from the value.
+        // This is how the BIOS flushes the cache: it stores 0 to every line.
+        line.tag = lower >> 12;
+        for (u32 i = 0; i < 4; i++)
+            line.valid[i] = value & (1 << i);
+    } else {
+        // Otherwise the store goes straight to the cached data, only the
+        // bytes being stored change
+        memcpy((u8 *) &line.data[word] + (lower & 3), &value, sizeof(T));
+    }
 }
 
 template <typename T>
@@ -17,33 +186,21 @@ T Memory::read(u32 address) {
     // The MIPS doesn't allow unaligned access
     FAIL_IF(address & (sizeof(T) - 1), "MEM", "Unaligned memory access");
 
-    u32 lower = normalizeAddress(address);
+    u32 lower;
+    if (translate(address, lower)) {
+        // Fast path: RAM and BIOS are plain memory
+        u8 *page = readPages[lower >> pageShift];
+        if (page)
+            return *(T *) &page[lower & (pageSize - 1)];
 
-    T *pointer = nullptr;
-
-    switch (lower) {
-        case 0x00000000 ... 0x007ffffc:
-            // Main memory is mirrored at the 0th, 2nd, 4th and 6th MB
-            // Remove bits 21 and 22 to get the physical address.
-            pointer = (T *) &mainRam[lower & ~0x00600000];
-            break;
-        case 0x1f800000 ... 0x1f80fffc:
-            return psx.ioRead<T>(address & 0xffff);
-        case 0x1fc00000 ... 0x1fc7fffc:
-            pointer = (T *) &biosRom[lower - 0x1fc00000];
-            break;
-        case 0x1f000000 ... 0x1f7ffffc:
-            // Reads from expansion memory 1 when no expanions is present are all 1s
-            return (T) -1;
-        case 0x1ffe0130:
-            // TODO: Cache Control
-            pointer = (T *) &cacheControl;
-            break;
-        default:
-            emuPanic("MEM", std::stringstream() << "Access to undefined address " << std::hex << address);
+        const DeviceMapping &mapping = deviceAt(lower);
+        if (mapping.device)
+            return mapping.device->read(lower - mapping.base, sizeof(T));
     }
 
-    return *pointer;
+    log('W', "MEM", std::stringstream() << "Bus error reading from " << std::hex << address);
+    busError = true;
+    return 0;
 }
 
 template <typename T>
@@ -51,28 +208,95 @@ void Memory::write(u32 address, T value) {
     // The MIPS doesn't allow unaligned access
     FAIL_IF(address & (sizeof(T) - 1), "MEM", "Unaligned memory access");
 
-    u32 lower = normalizeAddress(address);
-
-    T *pointer = nullptr;
+    u32 lower;
+    if (translate(address, lower)) {
+        u8 *page = writePages[lower >> pageShift];
+        if (page) {
+            *(T *) &page[lower & (pageSize - 1)] = value;
+            return;
+        }
 
-    switch (lower) {
-        case 0x00000000 ... 0x007ffffc:
-            pointer = (T *) &mainRam[lower & ~0x00600000];
-            break;
-        case 0x1f800000 ... 0x1f80fffc:
-            psx.ioWrite(address & 0xffff, value);
+        const DeviceMapping &mapping = deviceAt(lower);
+        if (mapping.device) {
+            mapping.device->write(lower - mapping.base, value, sizeof(T));
             return;
-        case 0x1fc00000 ... 0x1fc7fffc:
-            emuPanic("MEM", std::stringstream() << "Trying to write in ROM area at " << std::hex << address);
-        case 0x1ffe0130:
-            // TODO: Cache Control
-            pointer = (T *) &cacheControl;
-            break;
-        default:
-            emuPanic("MEM", std::stringstream() << "Access to undefined address " << std::hex << address);
+        }
+    }
+
+    log('W', "MEM", std::stringstream() << "Bus error writing to " << std::hex << address);
+    busError = true;
+}
+
+u32 Memory::Scratchpad::read(u32 address, u32 width) {
+    // When disabled, nothing answers in the scratchpad area
+    if (!memory.scratchpadEnabled())
+        return 0xffffffff;
+
+    u32 value = 0;
+    memcpy(&value, &data[address], width);
+    return value;
+}
+
+void Memory::Scratchpad::write(u32 address, u32 value, u32 width) {
+    if (memory.scratchpadEnabled())
+        memcpy(&data[address], &value, width);
+}
+
+// Only reachable at CACHE_CONTROL itself, see translate()
+u32 Memory::CacheControl::read(u32 address, u32 width) {
+    return extract(memory.cacheControl, address, width);
+}
+
+void Memory::CacheControl::write(u32 address, u32 value, u32 width) {
+    memory.cacheControl = merge(memory.cacheControl, address, value, width);
+}
+
+bool Memory::peek(u32 address, void *buffer, u32 length) {
+    u8 *output = (u8 *) buffer;
+
+    while (length > 0) {
+        u32 lower;
+        if (!translate(address, lower))
+            return false;
+
+        u8 *page = readPages[lower >> pageShift];
+        if (!page)
+            return false;
+
+        // Copy up to the end of the page, the next one may be elsewhere
+        u32 offset = lower & (pageSize - 1);
+        u32 chunk = std::min(length, pageSize - offset);
+        memcpy(output, &page[offset], chunk);
+
+        output += chunk;
+        address += chunk;
+        length -= chunk;
+    }
+
+    return true;
+}
+
+bool Memory::readString(u32 address, std::string &string, u32 maxLength) {
+    string.clear();
+
+    for (u32 i = 0; i < maxLength; i++) {
+        u32 lower;
+        if (!translate(address + i, lower))
+            return false;
+
+        u8 *page = readPages[lower >> pageShift];
+        if (!page)
+            return false;
+
+        char c = page[lower & (pageSize - 1)];
+        if (c == '\0')
+            return true;
+
+        string += c;
     }
 
-    *pointer = value;
+    // Not terminated within maxLength, keep what was read
+    return false;
 }
 
 void Memory::loadBios(std::string path) {
@@ -86,6 +310,79 @@ void Memory::loadBios(std::string path) {
         emuPanic("MEM", "Could not open the BIOS file");
 }
 
+void Memory::setPowerOnRam(PowerOnState state, u32 seed) {
+    powerOnState = state;
+    powerOnSeed = seed;
+}
+
+void Memory::fillRam() {
+    if (powerOnState == PowerOnState::Zero) {
+        std::fill(mainRam, mainRam + ramSize, 0);
+        std::fill(scratchpad.data, scratchpad.data + SCRATCHPAD_SIZE, 0);
+        return;
+    }
+
+    // mt19937 output is fully specified by the standard, so a seed gives the
+    // same RAM contents on every platform. The scratchpad comes after main
+    // RAM, which keeps the RAM contents for a seed as they were.
+    std::mt19937 generator(powerOnSeed);
+    auto fill = [&generator](u8 *memory, u32 size) {
+        for (u32 i = 0; i < size; i += 4) {
+            u32 word = generator();
+            memory[i + 0] = word;
+            memory[i + 1] = word >> 8;
+            memory[i + 2] = word >> 16;
+            memory[i + 3] = word >> 24;
+        }
+    };
+
+    fill(mainRam, ramSize);
+    fill(scratchpad.data, SCRATCHPAD_SIZE);
+}
+
+void Memory::setRamSize(u32 size) {
+    FAIL_IF(size != RAM_SIZE && size != RAM_SIZE_DEVKIT, "MEM", "Unsupported RAM size");
+
+    ramSize = size;
+    mapPages();
+
+    // The new RAM needs the power-on contents too
+    fillRam();
+}
+
+void Memory::loadExpansion(std::string path) {
+    std::ifstream rom(path, std::ios::binary | std::ios::ate);
+
+    if (!rom.is_open())
+        emuPanic("MEM", "Could not open the expansion ROM file");
+
+    u32 size = rom.tellg();
+    if (size == 0 || size > EXPANSION1_SIZE)
+        emuPanic("MEM", "Invalid expansion ROM size");
+
+    // Pad with 1s, what an erased flash chip would read as
+    delete[] expansionRom;
+    expansionSize = (size + pageSize - 1) & ~(pageSize - 1);
+    expansionRom = new u8[expansionSize];
+    std::fill(expansionRom, expansionRom + expansionSize, 0xff);
+
+    rom.seekg(0);
+    rom.read((char *) expansionRom, size);
+    if (!rom.good())
+        emuPanic("MEM", "Could not read the expansion ROM file");
+
+    // The BIOS only jumps into the cartridge if it finds these IDs, either
+    // before (at 0x84) or after (at 0x04) initializing the kernel.
+    static const char id[] = "Licensed by Sony Computer Entertainment Inc.";
+    bool preBoot = size >= 0x84 + sizeof(id) - 1 && !memcmp(&expansionRom[0x84], id, sizeof(id) - 1);
+    bool postBoot = size >= 0x04 + sizeof(id) - 1 && !memcmp(&expansionRom[0x04], id, sizeof(id) - 1);
+
+    if (!preBoot && !postBoot)
+        log('W', "MEM", "The expansion ROM has no license string, the BIOS will not run it");
+
+    mapPages();
+}
+
 // Implement the above templates.
 template u8 Memory::read<u8>(uint32_t address);
 template u16 Memory::read<u16>(uint32_t address);
@@ -95,7 +392,29 @@ template void Memory::write<u8>(uint32_t address, u8 value);
 template void Memory::write<u16>(uint32_t address, u16 value);
 template void Memory::write<u32>(uint32_t address, u32 value);
 
+template void Memory::cacheWrite<u8>(uint32_t address, u8 value);
+template void Memory::cacheWrite<u16>(uint32_t address, u16 value);
+template void Memory::cacheWrite<u32>(uint32_t address, u32 value);
+
 uint32_t Memory::normalizeAddress(uint32_t address) {
     // Strip out the useless bits (KSEG#)
     return address & 0x1fffffff;
 }
+
+bool Memory::translate(u32 address, u32 &lower) {
+    lower = normalizeAddress(address);
+
+    // KSEG2 has nothing but the cache control register. It sits in the page
+    // table at its lower bits, in a page the other segments can't reach.
+    if (address >= 0xc0000000)
+        return address == CACHE_CONTROL;
+
+    if ((lower >> pageShift) == (normalizeAddress(CACHE_CONTROL) >> pageShift))
+        return false;
+
+    // The scratchpad is the data cache, it can't be reached uncached (KSEG1)
+    if (address >= 0xa0000000 && lower - SCRATCHPAD_BASE < SCRATCHPAD_SIZE)
+        return false;
+
+    return true;
+}
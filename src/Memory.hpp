@@ -1,20 +1,56 @@
 #pragma once
 
 #include "types.hpp"
+#include "BusDevice.hpp"
+#include "Reset.hpp"
 
 #define RAM_SIZE              (2 * 1024 * 1024)
+#define RAM_SIZE_DEVKIT       (8 * 1024 * 1024)
 #define BIOS_SIZE             (512 * 1024)
 
 #define BIOS_BASE             0x1fc00000
 
-class Emulator;
+#define SCRATCHPAD_BASE       0x1f800000
+#define SCRATCHPAD_SIZE       1024
+
+// Instruction cache geometry: 256 lines of four words
+#define ICACHE_LINES          256
+
+#define EXPANSION1_BASE       0x1f000000
+#define EXPANSION1_SIZE       (8 * 1024 * 1024)
+
+#define EXPANSION3_BASE       0x1fa00000
+#define EXPANSION3_SIZE       (2 * 1024 * 1024)
+
+#define CACHE_CONTROL         0xfffe0130
+
+// What main RAM contains when the console is switched on
+enum class PowerOnState {
+    // Every byte is zero. Some games rely on this (but real hardware doesn't)
+    Zero,
+    // Garbage generated from a seed, so that runs can be reproduced
+    Random
+};
 
 class Memory {
 public:
-    Memory(Emulator &psx);
+    Memory();
     ~Memory();
 
+    void reset(ResetKind kind);
     void loadBios(std::string path);
+    void loadExpansion(std::string path);
+
+    // Retail consoles have RAM_SIZE, development units RAM_SIZE_DEVKIT
+    void setRamSize(u32 size);
+
+    // What main RAM and the scratchpad hold after a power cycle, the next
+    // reset(PowerCycle) applies it
+    void setPowerOnRam(PowerOnState state, u32 seed);
+
+    // Route accesses to [base, base + size) to a device. Outside of the I/O
+    // page, mappings are done in whole 64KB pages.
+    void map(u32 base, u32 size, BusDevice *device);
 
     template <typename T>
     T read(u32 address);
@@ -22,13 +58,128 @@ public:
     template <typename T>
     void write(u32 address, T value);
 
+    // Accesses to unmapped addresses don't stop the emulator, they flag a
+    // bus error instead. This returns (and clears) that flag.
+    bool takeBusError();
+
+    // Instruction fetches go through the instruction cache when enabled
+    u32 fetch(u32 address);
+
+    // Stores done while the CPU has the cache isolated (SR.IsC)
+    template <typename T>
+    void cacheWrite(u32 address, T value);
+
+    // Helpers for tooling (debugger, tracers...). They only see RAM and BIOS,
+    // never touch I/O and never panic: false means part of the range is not
+    // plain memory.
+    bool peek(u32 address, void *buffer, u32 length);
+    bool readString(u32 address, std::string &string, u32 maxLength = 256);
+
+    template <typename T>
+    bool readStruct(u32 address, T &value) {
+        return peek(address, &value, sizeof(T));
+    }
+
+    template <typename T>
+    bool readArray(u32 address, T *values, u32 count) {
+        // The length would wrap, and only part of the array be read
+        if (count > UINT32_MAX / sizeof(T))
+            return false;
+
+        return peek(address, values, count * sizeof(T));
+    }
+
 private:
-    Emulator &psx;
+    // The physical address space is split in 64KB pages for the fast path
+    static constexpr u32 pageShift = 16;
+    static constexpr u32 pageSize = 1 << pageShift;
+    static constexpr u32 pageCount = 0x20000000 >> pageShift;
+
+    // The I/O page hosts many devices, it is split further in 16-byte slots
+    static constexpr u32 ioPage = 0x1f800000 >> pageShift;
+    static constexpr u32 ioSlotShift = 4;
+    static constexpr u32 ioSlotCount = pageSize >> ioSlotShift;
 
     uint32_t normalizeAddress(u32 address);
 
+    // Find where an access goes in the page table. False means nothing can
+    // answer at that address, whatever is mapped there.
+    bool translate(u32 address, u32 &lower);
+    void mapPages();
+    void fillRam();
+
     u8 *mainRam;
+    u32 ramSize;
     u8 *biosRom;
 
+    // Cartridge ROM in the parallel port, if any (rounded up to whole pages)
+    u8 *expansionRom;
+    u32 expansionSize;
+
+    // Host pointers for the pages that are plain memory (RAM and its mirrors,
+    // the BIOS for reads). nullptr means the access goes to a device.
+    u8 **readPages;
+    u8 **writePages;
+
+    struct DeviceMapping {
+        BusDevice *device;
+        u32 base;
+    };
+
+    // Devices for the pages that are not plain memory, and for the I/O slots.
+    // Anything without a device is a bus error.
+    DeviceMapping *devicePages;
+    DeviceMapping *ioDevices;
+
+    const DeviceMapping &deviceAt(u32 lower);
+
+    // The data cache, used as fast RAM
+    class Scratchpad : public BusDevice {
+    public:
+        Scratchpad(Memory &memory) : memory(memory) {}
+
+        u32 read(u32 address, u32 width) override;
+        void write(u32 address, u32 value, u32 width) override;
+
+        u8 data[SCRATCHPAD_SIZE];
+
+    private:
+        Memory &memory;
+    };
+
+    // The cache control register, the only thing in KSEG2
+    class CacheControl : public BusDevice {
+    public:
+        CacheControl(Memory &memory) : memory(memory) {}
+
+        u32 read(u32 address, u32 width) override;
+        void write(u32 address, u32 value, u32 width) override;
+
+    private:
+        Memory &memory;
+    };
+
+    Scratchpad scratchpad;
+    CacheControl cacheControlPort;
+    OpenBus openBus;
+
     u32 cacheControl;
+
+    bool busError;
+
+    bool scratchpadEnabled();
+    bool codeCacheEnabled();
+    bool tagTestEnabled();
+
+    struct CacheLine {
+        u32 tag;
+        bool valid[4];
+        u32 data[4];
+    };
+
+    CacheLine icache[ICACHE_LINES];
+
+    // Remembered so that the same garbage can be recreated
+    PowerOnState powerOnState;
+    u32 powerOnSeed;
 };
@@ -0,0 +1,8 @@
+#pragma once
+
+enum class ResetKind {
+    // Like flipping the power switch: memories are lost as well
+    PowerCycle,
+    // Like pressing the reset button: memories survive, registers don't
+    Soft
+};
@@ -1,15 +1,68 @@
+#include <cctype>
+#include <cerrno>
+#include <cstdlib>
 #include <iostream>
+#include <string>
 
 #include "Emulator.hpp"
 #include "types.hpp"
 
 using namespace std;
 
-int main() {
+static int usage(const char *name) {
+    cerr << "Usage: " << name << " [--seed N] [--exp1 FILE] [--8mb]" << endl;
+    cerr << "  --seed N       fill RAM with garbage generated from N at power on" << endl;
+    cerr << "                 (32 bits, decimal or 0x-prefixed hex)" << endl;
+    cerr << "  --exp1 FILE    plug a cartridge ROM in the parallel port" << endl;
+    cerr << "  --8mb          8MB of RAM, like development consoles" << endl;
+    return 1;
+}
+
+// Whole argument, decimal or 0x-prefixed hex, and it has to fit in 32 bits.
+// Anything else would quietly seed something other than what was asked.
+static bool parseSeed(const char *text, u32 &seed) {
+    // strtoull would skip spaces and negate a leading '-'
+    if (!isdigit((unsigned char) text[0]))
+        return false;
+
+    bool hex = text[0] == '0' && (text[1] == 'x' || text[1] == 'X');
+    char *end;
+
+    errno = 0;
+    unsigned long long value = strtoull(text, &end, hex ? 16 : 10);
+
+    if (*end != '\0' || errno || value > UINT32_MAX)
+        return false;
+
+    seed = value;
+    return true;
+}
+
+int main(int argc, char **argv) {
     Emulator psx;
 
+    for (int i = 1; i < argc; i++) {
+        string arg = argv[i];
+
+        if (arg == "--seed" && i + 1 < argc) {
+            u32 seed;
+            if (!parseSeed(argv[++i], seed))
+                return usage(argv[0]);
+
+            psx.setPowerOnRam(PowerOnState::Random, seed);
+        } else if (arg == "--exp1" && i + 1 < argc)
+            psx.loadExpansion(argv[++i]);
+        else if (arg == "--8mb")
+            psx.setRamSize(RAM_SIZE_DEVKIT);
+        else
+            return usage(argv[0]);
+    }
+
+    // The options above change what the console looks like when switched on
+    psx.reset(ResetKind::PowerCycle);
+
     psx.loadBios("SCPH1001.BIN");
     psx.run();
 
 	return 0;
-}
\ No newline at end of file
+}